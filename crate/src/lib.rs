@@ -11,14 +11,17 @@ extern crate wasm_bindgen;
 extern crate web_sys;
 
 use fnv::FnvHashMap;
-use js_sys::{Float32Array, WebAssembly};
-use nalgebra::{Matrix4, Translation, Vector3};
+use js_sys::{Float32Array, Uint16Array, WebAssembly};
+use nalgebra::{Matrix3, Matrix4, Translation, Vector3};
 use specs::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{
-    console, HtmlCanvasElement, WebGl2RenderingContext, WebGlProgram, WebGlShader,
-    WebGlUniformLocation, WebGlVertexArrayObject,
+    console, HtmlCanvasElement, WebGl2RenderingContext, WebGlBuffer, WebGlProgram, WebGlShader,
+    WebGlTexture, WebGlUniformLocation, WebGlVertexArrayObject,
 };
 
 macro_rules! console_log {
@@ -58,6 +61,65 @@ impl Default for ProjectionMatrix {
     }
 }
 
+/// Time elapsed since the previous `requestAnimationFrame` callback, in
+/// seconds, along with the total time elapsed since the first frame.
+#[derive(Debug, Default)]
+struct Time {
+    delta: f32,
+    elapsed: f32,
+}
+
+/// A single directional light, read by `RenderSystem` and fed to any
+/// renderer whose shader declares `uLightDirection`/`uLightColor`.
+#[derive(Debug)]
+struct DirectionalLight {
+    direction: Vector3<f32>,
+    color: Vector3<f32>,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        DirectionalLight {
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            color: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Everything that can go wrong setting up or compiling a `RenderSystem`,
+/// carrying enough detail to surface an actionable message in the console
+/// instead of an opaque wasm panic.
+#[derive(Debug)]
+enum RenderError {
+    NoCanvas,
+    ContextCreation,
+    ShaderCompile(String),
+    ProgramLink(String),
+    DuplicateRenderer(String),
+    Resource(String),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RenderError::NoCanvas => write!(f, "No canvas specified"),
+            RenderError::ContextCreation => write!(f, "Unable to acquire a webgl2 context"),
+            RenderError::ShaderCompile(message) => write!(f, "Shader compile error: {}", message),
+            RenderError::ProgramLink(message) => write!(f, "Program link error: {}", message),
+            RenderError::DuplicateRenderer(id) => {
+                write!(f, "Multiple renderers registered with id {}", id)
+            }
+            RenderError::Resource(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<RenderError> for JsValue {
+    fn from(error: RenderError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
 struct RenderSystem {
     gl: WebGl2RenderingContext,
     renderers: FnvHashMap<String, Renderable>,
@@ -66,22 +128,54 @@ struct RenderSystem {
 impl<'a> System<'a> for RenderSystem {
     type SystemData = (
         Write<'a, ProjectionMatrix>, // Ideally would be Read, see https://github.com/rustwasm/wasm-bindgen/issues/978
+        Read<'a, DirectionalLight>,
         ReadStorage<'a, Pos>,
         ReadStorage<'a, Rendered>,
+        ReadStorage<'a, Uniforms>,
     );
 
-    fn run(&mut self, (mut projection_matrix, positions, rendereds): Self::SystemData) {
+    fn run(
+        &mut self,
+        (mut projection_matrix, light, positions, rendereds, uniforms): Self::SystemData,
+    ) {
         self.gl.clear(
             WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT,
         );
-        (&positions, &rendereds)
+
+        let mut instance_translations: FnvHashMap<String, Vec<f32>> = FnvHashMap::default();
+        for (Pos(position), rendered) in (&positions, &rendereds).join() {
+            if let Some(renderer) = self.renderers.get(&rendered.renderable_id) {
+                if renderer.definition.instance_attrib_location.is_some() {
+                    instance_translations
+                        .entry(rendered.renderable_id.clone())
+                        .or_insert_with(Vec::new)
+                        .extend_from_slice(&[position.x, position.y, position.z]);
+                }
+            }
+        }
+
+        for (renderable_id, translations) in &instance_translations {
+            let renderer = self.renderers.get(renderable_id).unwrap();
+            Self::draw_instanced(&self.gl, renderer, &mut projection_matrix, &light, translations);
+        }
+
+        (&positions, &rendereds, uniforms.maybe())
             .join()
-            .for_each(|(Pos(position), rendered)| {
+            .filter(|(_, rendered, _)| {
+                self.renderers
+                    .get(&rendered.renderable_id)
+                    .map_or(false, |renderer| {
+                        renderer.definition.instance_attrib_location.is_none()
+                    })
+            })
+            .for_each(|(Pos(position), rendered, entity_uniforms)| {
                 let renderer = self.renderers.get(&rendered.renderable_id).unwrap();
 
                 self.gl.bind_vertex_array(Some(&renderer.vao));
 
                 for input in &renderer.definition.inputs {
+                    self.gl
+                        .bind_buffer(input.buffer_type, Some(&renderer.vertex_buffer));
                     self.gl.buffer_data_with_array_buffer_view(
                         input.buffer_type,
                         input.vertices.as_ref(),
@@ -89,6 +183,18 @@ impl<'a> System<'a> for RenderSystem {
                     );
                 }
 
+                if let Some(index_buffer) = &renderer.index_buffer {
+                    self.gl.bind_buffer(
+                        WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                        Some(index_buffer),
+                    );
+                    self.gl.buffer_data_with_array_buffer_view(
+                        WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                        renderer.definition.indices.as_ref().unwrap().as_ref(),
+                        WebGl2RenderingContext::STATIC_DRAW,
+                    );
+                }
+
                 let mut model_view_matrix = Translation::from_vector(*position).to_homogeneous();
 
                 self.gl.use_program(Some(&renderer.program));
@@ -104,16 +210,210 @@ impl<'a> System<'a> for RenderSystem {
                     false,
                     model_view_matrix.as_mut_slice(),
                 );
-                self.gl.draw_arrays(
-                    renderer.definition.draw_mode,
-                    0,
-                    renderer.definition.vertices_to_render,
-                );
+
+                if let Some(location) = &renderer.normal_matrix_location {
+                    let inverse_transpose = model_view_matrix
+                        .try_inverse()
+                        .unwrap_or_else(Matrix4::identity)
+                        .transpose();
+                    // `Matrix3::new` takes arguments in row-major reading order but stores
+                    // them column-major internally, so `as_mut_slice` below is already in
+                    // the layout `uniform_matrix3fv` expects with `transpose = false`.
+                    let mut normal_matrix = Matrix3::new(
+                        inverse_transpose[(0, 0)],
+                        inverse_transpose[(0, 1)],
+                        inverse_transpose[(0, 2)],
+                        inverse_transpose[(1, 0)],
+                        inverse_transpose[(1, 1)],
+                        inverse_transpose[(1, 2)],
+                        inverse_transpose[(2, 0)],
+                        inverse_transpose[(2, 1)],
+                        inverse_transpose[(2, 2)],
+                    );
+                    self.gl.uniform_matrix3fv_with_f32_array(
+                        Some(location),
+                        false,
+                        normal_matrix.as_mut_slice(),
+                    );
+                }
+
+                Self::set_light_uniforms(&self.gl, renderer, &light);
+                Self::bind_textures(&self.gl, renderer);
+
+                if let Some(Uniforms(entity_uniforms)) = entity_uniforms {
+                    for (name, uniform) in entity_uniforms {
+                        match renderer.uniform_locations.get(name) {
+                            Some(location) => Self::set_uniform(&self.gl, location, uniform),
+                            None => console_log!(
+                                "Entity references unknown uniform \"{}\" on renderer {}; skipping",
+                                name,
+                                renderer.definition.id
+                            ),
+                        }
+                    }
+                }
+
+                match &renderer.index_buffer {
+                    Some(_) => self.gl.draw_elements_with_i32(
+                        renderer.definition.draw_mode,
+                        renderer
+                            .definition
+                            .index_count
+                            .expect("indexed renderable must set index_count"),
+                        WebGl2RenderingContext::UNSIGNED_SHORT,
+                        0,
+                    ),
+                    None => self.gl.draw_arrays(
+                        renderer.definition.draw_mode,
+                        0,
+                        renderer.definition.vertices_to_render,
+                    ),
+                }
                 self.gl.bind_vertex_array(None);
             })
     }
 }
 
+impl RenderSystem {
+    fn draw_instanced(
+        gl: &WebGl2RenderingContext,
+        renderer: &Renderable,
+        projection_matrix: &mut ProjectionMatrix,
+        light: &DirectionalLight,
+        translations: &[f32],
+    ) {
+        // Per-entity state (the normal matrix, and any `Uniforms` component)
+        // varies per instance and can't be expressed as a single uniform set
+        // once for the whole batched draw call, so it's unsupported here.
+        // Renderer-wide state (textures, the directional light) applies
+        // uniformly across the batch and is handled below same as the
+        // non-instanced path.
+        if renderer.normal_matrix_location.is_some() {
+            console_log!(
+                "Renderer {} is instanced; per-instance normal matrices are not supported, skipping",
+                renderer.definition.id
+            );
+        }
+
+        let instance_count = (translations.len() / 3) as i32;
+        let instance_buffer = renderer
+            .instance_buffer
+            .as_ref()
+            .expect("instanced renderable must have an instance buffer");
+
+        let memory_buffer = wasm_bindgen::memory()
+            .dyn_into::<WebAssembly::Memory>()
+            .unwrap()
+            .buffer();
+        let translations_location = translations.as_ptr() as u32 / 4;
+        let translations_array = Float32Array::new(&memory_buffer).subarray(
+            translations_location,
+            translations_location + translations.len() as u32,
+        );
+
+        gl.bind_vertex_array(Some(&renderer.vao));
+
+        for input in &renderer.definition.inputs {
+            gl.bind_buffer(input.buffer_type, Some(&renderer.vertex_buffer));
+            gl.buffer_data_with_array_buffer_view(
+                input.buffer_type,
+                input.vertices.as_ref(),
+                WebGl2RenderingContext::STATIC_DRAW,
+            );
+        }
+
+        if let Some(index_buffer) = &renderer.index_buffer {
+            gl.bind_buffer(WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER, Some(index_buffer));
+            gl.buffer_data_with_array_buffer_view(
+                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                renderer.definition.indices.as_ref().unwrap().as_ref(),
+                WebGl2RenderingContext::STATIC_DRAW,
+            );
+        }
+
+        gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(instance_buffer));
+        gl.buffer_data_with_array_buffer_view(
+            WebGl2RenderingContext::ARRAY_BUFFER,
+            translations_array.as_ref(),
+            WebGl2RenderingContext::DYNAMIC_DRAW,
+        );
+
+        gl.use_program(Some(&renderer.program));
+
+        gl.uniform_matrix4fv_with_f32_array(
+            Some(&renderer.projection_matrix_location),
+            false,
+            projection_matrix.perspective.as_mut_slice(),
+        );
+
+        gl.uniform_matrix4fv_with_f32_array(
+            Some(&renderer.model_view_matrix_location),
+            false,
+            Matrix4::identity().as_mut_slice(),
+        );
+
+        Self::set_light_uniforms(gl, renderer, light);
+        Self::bind_textures(gl, renderer);
+
+        match &renderer.index_buffer {
+            Some(_) => gl.draw_elements_instanced_with_i32(
+                renderer.definition.draw_mode,
+                renderer
+                    .definition
+                    .index_count
+                    .expect("indexed renderable must set index_count"),
+                WebGl2RenderingContext::UNSIGNED_SHORT,
+                0,
+                instance_count,
+            ),
+            None => gl.draw_arrays_instanced(
+                renderer.definition.draw_mode,
+                0,
+                renderer.definition.vertices_to_render,
+                instance_count,
+            ),
+        }
+
+        gl.bind_vertex_array(None);
+    }
+
+    fn set_light_uniforms(gl: &WebGl2RenderingContext, renderer: &Renderable, light: &DirectionalLight) {
+        if let Some(location) = &renderer.light_direction_location {
+            let direction = light.direction;
+            gl.uniform3f(Some(location), direction.x, direction.y, direction.z);
+        }
+
+        if let Some(location) = &renderer.light_color_location {
+            let color = light.color;
+            gl.uniform3f(Some(location), color.x, color.y, color.z);
+        }
+    }
+
+    fn bind_textures(gl: &WebGl2RenderingContext, renderer: &Renderable) {
+        for bound_texture in &renderer.textures {
+            gl.active_texture(WebGl2RenderingContext::TEXTURE0 + bound_texture.unit);
+            gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&bound_texture.texture));
+            gl.uniform1i(Some(&bound_texture.sampler_location), bound_texture.unit as i32);
+        }
+    }
+
+    fn set_uniform(
+        gl: &WebGl2RenderingContext,
+        location: &WebGlUniformLocation,
+        uniform: &Uniform,
+    ) {
+        match uniform {
+            Uniform::Float(x) => gl.uniform1f(Some(location), *x),
+            Uniform::Vec2(x, y) => gl.uniform2f(Some(location), *x, *y),
+            Uniform::Vec3(x, y, z) => gl.uniform3f(Some(location), *x, *y, *z),
+            Uniform::Vec4(x, y, z, w) => gl.uniform4f(Some(location), *x, *y, *z, *w),
+            Uniform::Mat4(values) => {
+                gl.uniform_matrix4fv_with_f32_array(Some(location), false, &mut values.clone())
+            }
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 struct RenderSystemBuilder {
     canvas: Option<HtmlCanvasElement>,
@@ -135,27 +435,24 @@ impl RenderSystemBuilder {
         self
     }
 
-    fn build(self) -> Result<RenderSystem, String> {
+    fn build(self) -> Result<RenderSystem, RenderError> {
         if let Some(canvas) = self.canvas {
             let gl = canvas
                 .get_context("webgl2")
-                .unwrap()
-                .unwrap()
+                .map_err(|_| RenderError::ContextCreation)?
+                .ok_or(RenderError::ContextCreation)?
                 .dyn_into::<WebGl2RenderingContext>()
-                .unwrap();
+                .map_err(|_| RenderError::ContextCreation)?;
 
             let mut renderers = FnvHashMap::default();
 
             for definition in self.definitions {
                 if renderers.contains_key(&definition.id) {
-                    return Err(
-                        format!("Multiple renderers registered with id {}", definition.id)
-                            .to_owned(),
-                    );
+                    return Err(RenderError::DuplicateRenderer(definition.id));
                 }
 
                 let renderer_id = definition.id.clone();
-                let renderer = Self::compile(&gl, definition).unwrap();
+                let renderer = Self::compile(&gl, definition)?;
                 renderers.insert(renderer_id, renderer);
             }
 
@@ -166,40 +463,109 @@ impl RenderSystemBuilder {
 
             Ok(RenderSystem { gl, renderers })
         } else {
-            Err("No canvas specified".to_owned())
+            Err(RenderError::NoCanvas)
         }
     }
 
     fn compile(
         gl: &WebGl2RenderingContext,
         definition: RenderableDefinition,
-    ) -> Result<Renderable, String> {
+    ) -> Result<Renderable, RenderError> {
         console_log!("Compiling render {}", definition.id);
         let vert_shader = Self::compile_shader(
             gl,
             WebGl2RenderingContext::VERTEX_SHADER,
             &definition.vertex_shader,
-        )
-        .unwrap();
+        )?;
 
         let frag_shader = Self::compile_shader(
             gl,
             WebGl2RenderingContext::FRAGMENT_SHADER,
             &definition.fragment_shader,
-        )
-        .unwrap();
+        )?;
 
-        let program = Self::link_program(gl, [vert_shader, frag_shader].iter()).unwrap();
+        let program = Self::link_program(gl, [vert_shader, frag_shader].iter())?;
 
         let projection_matrix_location = gl
             .get_uniform_location(&program, "uProjectionMatrix")
-            .unwrap();
+            .ok_or_else(|| {
+                RenderError::Resource("Unable to resolve uniform location uProjectionMatrix".to_owned())
+            })?;
         let model_view_matrix_location = gl
             .get_uniform_location(&program, "uModelViewMatrix")
-            .unwrap();
+            .ok_or_else(|| {
+                RenderError::Resource("Unable to resolve uniform location uModelViewMatrix".to_owned())
+            })?;
+
+        let normal_matrix_location = gl.get_uniform_location(&program, "uNormalMatrix");
+        let light_direction_location = gl.get_uniform_location(&program, "uLightDirection");
+        let light_color_location = gl.get_uniform_location(&program, "uLightColor");
 
-        let vao = gl.create_vertex_array().unwrap();
-        let buffer = gl.create_buffer().unwrap();
+        let mut uniform_locations = FnvHashMap::default();
+        for name in &definition.uniform_names {
+            let location = gl
+                .get_uniform_location(&program, name)
+                .ok_or_else(|| RenderError::Resource(format!("Unable to resolve uniform location {}", name)))?;
+            uniform_locations.insert(name.clone(), location);
+        }
+
+        let mut textures = Vec::new();
+        for texture_descriptor in &definition.textures {
+            let texture = gl
+                .create_texture()
+                .ok_or_else(|| RenderError::Resource("Unable to create texture object".to_owned()))?;
+            gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, Some(&texture));
+            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                WebGl2RenderingContext::TEXTURE_2D,
+                0,
+                WebGl2RenderingContext::RGBA as i32,
+                texture_descriptor.width,
+                texture_descriptor.height,
+                0,
+                WebGl2RenderingContext::RGBA,
+                WebGl2RenderingContext::UNSIGNED_BYTE,
+                Some(&texture_descriptor.pixels),
+            )
+            .map_err(|_| RenderError::Resource("Unable to upload texture data".to_owned()))?;
+            gl.tex_parameteri(
+                WebGl2RenderingContext::TEXTURE_2D,
+                WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+                WebGl2RenderingContext::LINEAR as i32,
+            );
+            gl.tex_parameteri(
+                WebGl2RenderingContext::TEXTURE_2D,
+                WebGl2RenderingContext::TEXTURE_WRAP_S,
+                WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+            );
+            gl.tex_parameteri(
+                WebGl2RenderingContext::TEXTURE_2D,
+                WebGl2RenderingContext::TEXTURE_WRAP_T,
+                WebGl2RenderingContext::CLAMP_TO_EDGE as i32,
+            );
+
+            let sampler_location = gl
+                .get_uniform_location(&program, &texture_descriptor.sampler_uniform)
+                .ok_or_else(|| {
+                    RenderError::Resource(format!(
+                        "Unable to resolve uniform location {}",
+                        texture_descriptor.sampler_uniform
+                    ))
+                })?;
+
+            textures.push(BoundTexture {
+                unit: texture_descriptor.unit,
+                texture,
+                sampler_location,
+            });
+        }
+        gl.bind_texture(WebGl2RenderingContext::TEXTURE_2D, None);
+
+        let vao = gl
+            .create_vertex_array()
+            .ok_or_else(|| RenderError::Resource("Unable to create vertex array object".to_owned()))?;
+        let buffer = gl
+            .create_buffer()
+            .ok_or_else(|| RenderError::Resource("Unable to create vertex buffer".to_owned()))?;
 
         gl.bind_vertex_array(Some(&vao));
 
@@ -216,14 +582,55 @@ impl RenderSystemBuilder {
             );
         }
 
+        let instance_buffer = if let Some(location) = definition.instance_attrib_location {
+            let instance_buffer = gl
+                .create_buffer()
+                .ok_or_else(|| RenderError::Resource("Unable to create instance buffer".to_owned()))?;
+            gl.bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&instance_buffer));
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_with_i32(
+                location,
+                3,
+                WebGl2RenderingContext::FLOAT,
+                false,
+                0,
+                0,
+            );
+            gl.vertex_attrib_divisor(location, 1);
+            Some(instance_buffer)
+        } else {
+            None
+        };
+
+        let index_buffer = if definition.indices.is_some() {
+            let index_buffer = gl
+                .create_buffer()
+                .ok_or_else(|| RenderError::Resource("Unable to create index buffer".to_owned()))?;
+            gl.bind_buffer(
+                WebGl2RenderingContext::ELEMENT_ARRAY_BUFFER,
+                Some(&index_buffer),
+            );
+            Some(index_buffer)
+        } else {
+            None
+        };
+
         gl.bind_vertex_array(None);
 
         Ok(Renderable {
             definition,
             program,
             vao,
+            vertex_buffer: buffer,
             projection_matrix_location,
             model_view_matrix_location,
+            uniform_locations,
+            textures,
+            index_buffer,
+            instance_buffer,
+            normal_matrix_location,
+            light_direction_location,
+            light_color_location,
         })
     }
 
@@ -231,10 +638,10 @@ impl RenderSystemBuilder {
         gl: &WebGl2RenderingContext,
         shader_type: u32,
         source: &str,
-    ) -> Result<WebGlShader, String> {
+    ) -> Result<WebGlShader, RenderError> {
         let shader = gl
             .create_shader(shader_type)
-            .ok_or_else(|| String::from("Unable to create shader object"))?;
+            .ok_or_else(|| RenderError::ShaderCompile("Unable to create shader object".to_owned()))?;
         gl.shader_source(&shader, source);
         gl.compile_shader(&shader);
 
@@ -245,19 +652,20 @@ impl RenderSystemBuilder {
         {
             Ok(shader)
         } else {
-            Err(gl
-                .get_shader_info_log(&shader)
-                .unwrap_or_else(|| "Unknown error creating shader".into()))
+            Err(RenderError::ShaderCompile(
+                gl.get_shader_info_log(&shader)
+                    .unwrap_or_else(|| "Unknown error creating shader".into()),
+            ))
         }
     }
 
     fn link_program<'a, T: IntoIterator<Item = &'a WebGlShader>>(
         gl: &WebGl2RenderingContext,
         shaders: T,
-    ) -> Result<WebGlProgram, String> {
+    ) -> Result<WebGlProgram, RenderError> {
         let program = gl
             .create_program()
-            .ok_or_else(|| String::from("Unable to create shader object"))?;
+            .ok_or_else(|| RenderError::ProgramLink("Unable to create program object".to_owned()))?;
         for shader in shaders {
             gl.attach_shader(&program, shader)
         }
@@ -270,9 +678,10 @@ impl RenderSystemBuilder {
         {
             Ok(program)
         } else {
-            Err(gl
-                .get_program_info_log(&program)
-                .unwrap_or_else(|| "Unknown error creating program object".into()))
+            Err(RenderError::ProgramLink(
+                gl.get_program_info_log(&program)
+                    .unwrap_or_else(|| "Unknown error creating program object".into()),
+            ))
         }
     }
 }
@@ -283,6 +692,15 @@ struct RenderableDefinition {
     fragment_shader: String,
     vertex_shader: String,
     inputs: Vec<InputDescriptor>,
+    uniform_names: Vec<String>,
+    textures: Vec<TextureDescriptor>,
+    indices: Option<Uint16Array>,
+    /// Number of indices to draw when `indices` is set. Distinct from
+    /// `vertices_to_render`, which is a vertex count and is used instead
+    /// when there's no index buffer — the two differ for any indexed mesh
+    /// that reuses vertices.
+    index_count: Option<i32>,
+    instance_attrib_location: Option<u32>,
     draw_mode: u32,
     vertices_to_render: i32,
 }
@@ -296,13 +714,44 @@ struct InputDescriptor {
     vertices: Float32Array,
 }
 
+/// A 2D texture to be uploaded during `compile` and bound to `unit` before
+/// each draw call, with its sampler uniform resolved by name.
+///
+/// Texture coordinates aren't a dedicated field here — they're supplied like
+/// any other per-vertex attribute, via an `InputDescriptor` with
+/// `num_components: 2` bound to the shader's `texture_coord` attribute
+/// location (see the `hexTileTextured` definition in `run` for an example).
+#[derive(Debug)]
+struct TextureDescriptor {
+    unit: u32,
+    sampler_uniform: String,
+    pixels: Vec<u8>,
+    width: i32,
+    height: i32,
+}
+
+#[derive(Debug)]
+struct BoundTexture {
+    unit: u32,
+    texture: WebGlTexture,
+    sampler_location: WebGlUniformLocation,
+}
+
 #[derive(Debug)]
 struct Renderable {
     definition: RenderableDefinition,
     program: WebGlProgram,
     vao: WebGlVertexArrayObject,
+    vertex_buffer: WebGlBuffer,
     projection_matrix_location: WebGlUniformLocation,
     model_view_matrix_location: WebGlUniformLocation,
+    uniform_locations: FnvHashMap<String, WebGlUniformLocation>,
+    textures: Vec<BoundTexture>,
+    index_buffer: Option<WebGlBuffer>,
+    instance_buffer: Option<WebGlBuffer>,
+    normal_matrix_location: Option<WebGlUniformLocation>,
+    light_direction_location: Option<WebGlUniformLocation>,
+    light_color_location: Option<WebGlUniformLocation>,
 }
 
 #[derive(Debug)]
@@ -320,14 +769,42 @@ impl Component for Pos {
     type Storage = VecStorage<Self>;
 }
 
+/// A single named shader uniform value, resolved against the uniform
+/// locations declared on a `RenderableDefinition`.
+#[derive(Debug, Clone, Copy)]
+enum Uniform {
+    Float(f32),
+    Vec2(f32, f32),
+    Vec3(f32, f32, f32),
+    Vec4(f32, f32, f32, f32),
+    Mat4([f32; 16]),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Uniforms(FnvHashMap<String, Uniform>);
+impl Component for Uniforms {
+    type Storage = DenseVecStorage<Self>;
+}
+
+fn request_animation_frame(f: &Closure<dyn FnMut(f64)>) {
+    web_sys::window()
+        .unwrap()
+        .request_animation_frame(f.as_ref().unchecked_ref())
+        .expect("should register requestAnimationFrame");
+}
+
 #[wasm_bindgen]
-pub fn draw() {
-    let document = web_sys::window().unwrap().document().unwrap();
-    let canvas = document.get_element_by_id("canvas").unwrap();
+pub fn run() -> Result<(), JsValue> {
+    let document = web_sys::window()
+        .ok_or(RenderError::NoCanvas)?
+        .document()
+        .ok_or(RenderError::NoCanvas)?;
+    let canvas = document
+        .get_element_by_id("canvas")
+        .ok_or(RenderError::NoCanvas)?;
     let canvas: web_sys::HtmlCanvasElement = canvas
         .dyn_into::<web_sys::HtmlCanvasElement>()
-        .map_err(|_| ())
-        .unwrap();
+        .map_err(|_| RenderError::NoCanvas)?;
 
     let vertices: [f32; 16] = [
         0.0_f32,
@@ -348,9 +825,17 @@ pub fn draw() {
         -0.5_f32,
     ];
 
+    // The hex tile is flat in the xy-plane, so every vertex shares the same
+    // +z normal; vertex_color is left at white so lighting is the only thing
+    // modulating the output.
+    let normals: [f32; 24] = [
+        0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0,
+        0.0, 0.0, 1.0, 0.0, 0.0, 1.0,
+    ];
+    let colors: [f32; 24] = [1.0_f32; 24];
+
     let memory_buffer = wasm_bindgen::memory()
-        .dyn_into::<WebAssembly::Memory>()
-        .unwrap()
+        .dyn_into::<WebAssembly::Memory>()?
         .buffer();
 
     let vertices_location = vertices.as_ptr() as u32 / 4;
@@ -358,17 +843,100 @@ pub fn draw() {
     let vert_array = Float32Array::new(&memory_buffer)
         .subarray(vertices_location, vertices_location + vertices.len() as u32);
 
+    let normals_location = normals.as_ptr() as u32 / 4;
+    let normal_array = Float32Array::new(&memory_buffer)
+        .subarray(normals_location, normals_location + normals.len() as u32);
+
+    let colors_location = colors.as_ptr() as u32 / 4;
+    let color_array = Float32Array::new(&memory_buffer)
+        .subarray(colors_location, colors_location + colors.len() as u32);
+
     let render_definition = RenderableDefinition {
         id: "hexTile".to_owned(),
         draw_mode: WebGl2RenderingContext::TRIANGLE_FAN,
         vertex_shader: r#"#version 300 es
             layout(location = 0) in vec4 position;
+            layout(location = 1) in vec3 vertex_normal;
+            layout(location = 2) in vec3 vertex_color;
 
             uniform mat4 uModelViewMatrix;
             uniform mat4 uProjectionMatrix;
+            uniform mat3 uNormalMatrix;
+
+            out vec3 vNormal;
+            out vec3 vColor;
 
             void main() {
               gl_Position = uProjectionMatrix * uModelViewMatrix * position;
+              vNormal = uNormalMatrix * vertex_normal;
+              vColor = vertex_color;
+            }"#
+        .to_owned(),
+        fragment_shader: r#"#version 300 es
+            precision mediump float;
+
+            in vec3 vNormal;
+            in vec3 vColor;
+
+            uniform vec3 uLightDirection;
+            uniform vec3 uLightColor;
+
+            out vec4 fragColor;
+
+            void main() {
+              float diffuse = max(dot(normalize(vNormal), normalize(-uLightDirection)), 0.0);
+              fragColor = vec4(vColor * uLightColor * diffuse, 1.0);
+            }"#
+        .to_owned(),
+        inputs: vec![
+            InputDescriptor {
+                location: 0,
+                buffer_type: WebGl2RenderingContext::ARRAY_BUFFER,
+                buffer_data_type: WebGl2RenderingContext::FLOAT,
+                num_components: 2,
+                vertices: vert_array,
+            },
+            InputDescriptor {
+                location: 1,
+                buffer_type: WebGl2RenderingContext::ARRAY_BUFFER,
+                buffer_data_type: WebGl2RenderingContext::FLOAT,
+                num_components: 3,
+                vertices: normal_array,
+            },
+            InputDescriptor {
+                location: 2,
+                buffer_type: WebGl2RenderingContext::ARRAY_BUFFER,
+                buffer_data_type: WebGl2RenderingContext::FLOAT,
+                num_components: 3,
+                vertices: color_array,
+            },
+        ],
+        uniform_names: vec![],
+        textures: vec![],
+        indices: None,
+        index_count: None,
+        instance_attrib_location: None,
+        vertices_to_render: 8,
+    };
+
+    let instanced_vertices_location = vertices.as_ptr() as u32 / 4;
+    let instanced_vert_array = Float32Array::new(&memory_buffer).subarray(
+        instanced_vertices_location,
+        instanced_vertices_location + vertices.len() as u32,
+    );
+
+    let instanced_render_definition = RenderableDefinition {
+        id: "hexTileInstanced".to_owned(),
+        draw_mode: WebGl2RenderingContext::TRIANGLE_FAN,
+        vertex_shader: r#"#version 300 es
+            layout(location = 0) in vec4 position;
+            layout(location = 1) in vec3 instanceTranslation;
+
+            uniform mat4 uModelViewMatrix;
+            uniform mat4 uProjectionMatrix;
+
+            void main() {
+              gl_Position = uProjectionMatrix * uModelViewMatrix * (position + vec4(instanceTranslation, 0.0));
             }"#
         .to_owned(),
         fragment_shader: r#"#version 300 es
@@ -384,16 +952,104 @@ pub fn draw() {
             buffer_type: WebGl2RenderingContext::ARRAY_BUFFER,
             buffer_data_type: WebGl2RenderingContext::FLOAT,
             num_components: 2,
-            vertices: vert_array,
+            vertices: instanced_vert_array,
+        }],
+        uniform_names: vec![],
+        textures: vec![],
+        indices: None,
+        index_count: None,
+        instance_attrib_location: Some(1),
+        vertices_to_render: 8,
+    };
+
+    // uv maps each hex vertex onto the unit square so the checkerboard
+    // texture below is actually sampled across the tile.
+    let texture_coords: [f32; 16] = [
+        0.5, 0.5, 1.0, 0.25, 1.0, 0.75, 0.5, 1.0, 0.0, 0.75, 0.0, 0.25, 0.5, 0.0, 1.0, 0.25,
+    ];
+    let texture_coords_location = texture_coords.as_ptr() as u32 / 4;
+    let texture_coords_array = Float32Array::new(&memory_buffer).subarray(
+        texture_coords_location,
+        texture_coords_location + texture_coords.len() as u32,
+    );
+
+    let textured_vertices_location = vertices.as_ptr() as u32 / 4;
+    let textured_vert_array = Float32Array::new(&memory_buffer).subarray(
+        textured_vertices_location,
+        textured_vertices_location + vertices.len() as u32,
+    );
+
+    // 2x2 RGBA checkerboard.
+    let checkerboard_pixels: Vec<u8> = vec![
+        255, 255, 255, 255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 255,
+    ];
+
+    let textured_render_definition = RenderableDefinition {
+        id: "hexTileTextured".to_owned(),
+        draw_mode: WebGl2RenderingContext::TRIANGLE_FAN,
+        vertex_shader: r#"#version 300 es
+            layout(location = 0) in vec4 position;
+            layout(location = 1) in vec2 texture_coord;
+
+            uniform mat4 uModelViewMatrix;
+            uniform mat4 uProjectionMatrix;
+
+            out vec2 vTextureCoord;
+
+            void main() {
+              gl_Position = uProjectionMatrix * uModelViewMatrix * position;
+              vTextureCoord = texture_coord;
+            }"#
+        .to_owned(),
+        fragment_shader: r#"#version 300 es
+            precision mediump float;
+
+            in vec2 vTextureCoord;
+
+            uniform sampler2D uSampler;
+
+            out vec4 fragColor;
+
+            void main() {
+              fragColor = texture(uSampler, vTextureCoord);
+            }"#
+        .to_owned(),
+        inputs: vec![
+            InputDescriptor {
+                location: 0,
+                buffer_type: WebGl2RenderingContext::ARRAY_BUFFER,
+                buffer_data_type: WebGl2RenderingContext::FLOAT,
+                num_components: 2,
+                vertices: textured_vert_array,
+            },
+            InputDescriptor {
+                location: 1,
+                buffer_type: WebGl2RenderingContext::ARRAY_BUFFER,
+                buffer_data_type: WebGl2RenderingContext::FLOAT,
+                num_components: 2,
+                vertices: texture_coords_array,
+            },
+        ],
+        uniform_names: vec![],
+        textures: vec![TextureDescriptor {
+            unit: 0,
+            sampler_uniform: "uSampler".to_owned(),
+            pixels: checkerboard_pixels,
+            width: 2,
+            height: 2,
         }],
+        indices: None,
+        index_count: None,
+        instance_attrib_location: None,
         vertices_to_render: 8,
     };
 
     let render_system = RenderSystemBuilder::new()
         .with_canvas(canvas)
         .register(render_definition)
-        .build()
-        .unwrap();
+        .register(instanced_render_definition)
+        .register(textured_render_definition)
+        .build()?;
 
     let mut world = World::new();
 
@@ -405,6 +1061,8 @@ pub fn draw() {
     world.add_resource(ProjectionMatrix {
         perspective: Matrix4::new_perspective(aspect_ratio, fov, z_near, z_far),
     });
+    world.add_resource(Time::default());
+    world.add_resource(DirectionalLight::default());
 
     let mut dispatcher = DispatcherBuilder::new()
         .with_thread_local(render_system)
@@ -420,9 +1078,61 @@ pub fn draw() {
         })
         .build();
 
-    dispatcher.dispatch(&world.res);
+    world
+        .create_entity()
+        .with(Pos(Vector3::new(-2.0, 0.0, -8.0)))
+        .with(Rendered {
+            renderable_id: "hexTileInstanced".to_owned(),
+        })
+        .build();
+
+    world
+        .create_entity()
+        .with(Pos(Vector3::new(2.0, 0.0, -8.0)))
+        .with(Rendered {
+            renderable_id: "hexTileInstanced".to_owned(),
+        })
+        .build();
+
+    world
+        .create_entity()
+        .with(Pos(Vector3::new(4.0, 0.0, -8.0)))
+        .with(Rendered {
+            renderable_id: "hexTileTextured".to_owned(),
+        })
+        .build();
+
+    let world = Rc::new(RefCell::new(world));
+    let dispatcher = Rc::new(RefCell::new(dispatcher));
+    // `None` until the first rAF callback supplies a timestamp;
+    // seeding it with that first timestamp (rather than 0.0) avoids a
+    // spurious delta/elapsed spike on the first frame.
+    let last_timestamp: Rc<RefCell<Option<f64>>> = Rc::new(RefCell::new(None));
+
+    let f = Rc::new(RefCell::new(None));
+    let g = f.clone();
+
+    *g.borrow_mut() = Some(Closure::wrap(Box::new(move |timestamp: f64| {
+        let delta = ((timestamp - last_timestamp.borrow().unwrap_or(timestamp)) / 1000.0) as f32;
+        *last_timestamp.borrow_mut() = Some(timestamp);
+
+        {
+            let world = world.borrow();
+            let mut time = world.write_resource::<Time>();
+            time.delta = delta;
+            time.elapsed += delta;
+        }
+
+        dispatcher.borrow_mut().dispatch(&world.borrow().res);
+
+        // Maintain dynamically added and removed entities in dispatch.
+        // This is what actually executes changes done by `LazyUpdate`.
+        world.borrow_mut().maintain();
+
+        request_animation_frame(f.borrow().as_ref().unwrap());
+    }) as Box<dyn FnMut(f64)>));
+
+    request_animation_frame(g.borrow().as_ref().unwrap());
 
-    // Maintain dynamically added and removed entities in dispatch.
-    // This is what actually executes changes done by `LazyUpdate`.
-    world.maintain();
+    Ok(())
 }